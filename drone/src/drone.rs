@@ -0,0 +1,179 @@
+use bincode::{deserialize, serialize};
+use serde_derive::{Deserialize, Serialize};
+use solana_sdk::hash::Hash;
+use solana_sdk::message::Message;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, KeypairUtil};
+use solana_sdk::system_instruction::SystemInstruction;
+use solana_sdk::transaction::Transaction;
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug)]
+enum DroneRequest {
+    GetAirdrop {
+        lamports: u64,
+        to: Pubkey,
+        blockhash: Hash,
+    },
+}
+
+/// Caps on how many lamports the drone will hand out to a single requesting
+/// pubkey. Defaults to effectively unlimited, matching the drone's original
+/// unconditional behavior.
+#[derive(Clone, Copy)]
+pub struct AirdropLimit {
+    pub max_lamports_per_request: u64,
+    pub window: Duration,
+    pub max_lamports_per_window: u64,
+}
+
+impl Default for AirdropLimit {
+    fn default() -> Self {
+        Self {
+            max_lamports_per_request: u64::max_value(),
+            window: Duration::from_secs(1),
+            max_lamports_per_window: u64::max_value(),
+        }
+    }
+}
+
+/// Tracks how many lamports each pubkey has been given within the
+/// configured sliding window, so the drone can reject requests that would
+/// drain it.
+struct RateLimiter {
+    limit: AirdropLimit,
+    history: Mutex<HashMap<Pubkey, Vec<(Instant, u64)>>>,
+}
+
+impl RateLimiter {
+    fn new(limit: AirdropLimit) -> Self {
+        Self {
+            limit,
+            history: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record `lamports` requested by `to`, rejecting the request (without
+    /// recording it) if it would exceed the per-request cap or `to`'s
+    /// sliding-window cap.
+    fn check_and_record(&self, to: &Pubkey, lamports: u64) -> Result<(), String> {
+        if lamports > self.limit.max_lamports_per_request {
+            return Err(format!(
+                "requested {} lamports exceeds the drone's per-request limit of {}",
+                lamports, self.limit.max_lamports_per_request
+            ));
+        }
+
+        let now = Instant::now();
+        let mut history = self.history.lock().unwrap();
+        let requests = history.entry(*to).or_insert_with(Vec::new);
+        requests.retain(|(at, _)| now.duration_since(*at) < self.limit.window);
+
+        let total_in_window: u64 = requests.iter().map(|(_, lamports)| *lamports).sum();
+        if total_in_window.saturating_add(lamports) > self.limit.max_lamports_per_window {
+            return Err(format!(
+                "{} has already received {} of {} lamports allowed per {:?}",
+                to, total_in_window, self.limit.max_lamports_per_window, self.limit.window
+            ));
+        }
+
+        requests.push((now, lamports));
+        Ok(())
+    }
+}
+
+/// Request an airdrop transaction of `lamports` to `to` from the drone
+/// listening at `drone_addr`. The returned transaction is unsigned by `to`
+/// and ready to submit as-is.
+pub fn request_airdrop_transaction(
+    drone_addr: &SocketAddr,
+    to: &Pubkey,
+    lamports: u64,
+    blockhash: Hash,
+) -> io::Result<Transaction> {
+    let mut stream = TcpStream::connect(drone_addr)?;
+    let request = DroneRequest::GetAirdrop {
+        lamports,
+        to: *to,
+        blockhash,
+    };
+    let bytes =
+        serialize(&request).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    stream.write_all(&bytes)?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut buffer = Vec::new();
+    stream.read_to_end(&mut buffer)?;
+    let response: Result<Transaction, String> =
+        deserialize(&buffer).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    response.map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+}
+
+/// Run a local drone that signs airdrop requests with `mint_keypair`,
+/// handing out lamports with no rate limit. The drone's bound address is
+/// sent over `sender` once it's listening.
+pub fn run_local_drone(mint_keypair: Keypair, sender: Sender<SocketAddr>) {
+    run_local_drone_with_limit(mint_keypair, sender, AirdropLimit::default())
+}
+
+/// Like `run_local_drone`, but rejecting requests that exceed `limit`
+/// instead of handing out lamports unconditionally.
+pub fn run_local_drone_with_limit(
+    mint_keypair: Keypair,
+    sender: Sender<SocketAddr>,
+    limit: AirdropLimit,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let local_addr = listener.local_addr().unwrap();
+    sender.send(local_addr).unwrap();
+
+    let mint_keypair = Arc::new(mint_keypair);
+    let rate_limiter = Arc::new(RateLimiter::new(limit));
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let mint_keypair = mint_keypair.clone();
+            let rate_limiter = rate_limiter.clone();
+            thread::spawn(move || handle_request(stream, &mint_keypair, &rate_limiter));
+        }
+    });
+}
+
+fn handle_request(mut stream: TcpStream, mint_keypair: &Keypair, rate_limiter: &RateLimiter) {
+    let mut buffer = Vec::new();
+    if stream.read_to_end(&mut buffer).is_err() {
+        return;
+    }
+    let request: DroneRequest = match deserialize(&buffer) {
+        Ok(request) => request,
+        Err(_) => return,
+    };
+
+    let DroneRequest::GetAirdrop {
+        lamports,
+        to,
+        blockhash,
+    } = request;
+    let response: Result<Transaction, String> =
+        rate_limiter.check_and_record(&to, lamports).map(|()| {
+            let create_instruction =
+                SystemInstruction::new_account(&mint_keypair.pubkey(), &to, lamports);
+            let message = Message::new(vec![create_instruction]);
+            Transaction::new(&[mint_keypair], message, blockhash)
+        });
+
+    if let Ok(bytes) = serialize(&response) {
+        let _ = stream.write_all(&bytes);
+    }
+}
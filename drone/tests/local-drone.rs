@@ -1,10 +1,13 @@
-use solana_drone::drone::{request_airdrop_transaction, run_local_drone};
+use solana_drone::drone::{
+    request_airdrop_transaction, run_local_drone, run_local_drone_with_limit, AirdropLimit,
+};
 use solana_sdk::hash::Hash;
 use solana_sdk::message::Message;
 use solana_sdk::signature::{Keypair, KeypairUtil};
 use solana_sdk::system_instruction::SystemInstruction;
 use solana_sdk::transaction::Transaction;
 use std::sync::mpsc::channel;
+use std::time::Duration;
 
 #[test]
 fn test_local_drone() {
@@ -23,3 +26,29 @@ fn test_local_drone() {
     let result = request_airdrop_transaction(&drone_addr, &to, lamports, blockhash);
     assert_eq!(expected_tx, result.unwrap());
 }
+
+#[test]
+fn test_local_drone_rejects_over_limit_request() {
+    let keypair = Keypair::new();
+    let to = Keypair::new().pubkey();
+    let blockhash = Hash::new(&to.as_ref());
+    let limit = AirdropLimit {
+        max_lamports_per_request: 100,
+        window: Duration::from_secs(60),
+        max_lamports_per_window: 100,
+    };
+
+    let (sender, receiver) = channel();
+    run_local_drone_with_limit(keypair, sender, limit);
+    let drone_addr = receiver.recv().unwrap();
+
+    assert!(request_airdrop_transaction(&drone_addr, &to, 50, blockhash).is_ok());
+    assert!(
+        request_airdrop_transaction(&drone_addr, &to, 500, blockhash).is_err(),
+        "a request over the per-request cap should be rejected"
+    );
+    assert!(
+        request_airdrop_transaction(&drone_addr, &to, 60, blockhash).is_err(),
+        "a request that would exceed the sliding-window cap should be rejected"
+    );
+}
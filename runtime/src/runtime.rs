@@ -1,9 +1,145 @@
 use crate::native_loader;
 use solana_sdk::account::{create_keyed_accounts, Account, KeyedAccount};
-use solana_sdk::instruction::InstructionError;
+use solana_sdk::instruction::{AccountMeta, Instruction, InstructionError};
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::system_program;
 use solana_sdk::transaction::{Transaction, TransactionError};
+use rayon::prelude::*;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+
+/// Default limit on how deeply one program may invoke another via
+/// cross-program invocation, to bound the call stack.
+const MAX_INVOKE_DEPTH: usize = 4;
+
+/// Meters the work a single transaction's instructions may perform, so a
+/// buggy or malicious program entrypoint can't loop forever or allocate
+/// unbounded memory.
+#[derive(Clone, Copy)]
+pub struct ComputeBudget {
+    /// Total compute units available to a transaction.
+    pub max_units: u64,
+    /// Fixed cost charged for every instruction that's executed.
+    pub instruction_cost: u64,
+    /// Cost charged per byte of instruction data and touched account data.
+    pub byte_cost: u64,
+}
+
+impl Default for ComputeBudget {
+    fn default() -> Self {
+        Self {
+            max_units: 200_000,
+            instruction_cost: 1_000,
+            byte_cost: 1,
+        }
+    }
+}
+
+/// Maximum total bytes of log messages collected per transaction, across all
+/// of its instructions, before further messages are dropped in favor of a
+/// truncation marker.
+const MAX_LOG_BYTES: usize = 10 * 1024;
+
+/// Maximum size of a single instruction's return-data blob.
+const MAX_RETURN_DATA_BYTES: usize = 1024;
+
+/// Collects the diagnostic messages and optional return-data blob a program
+/// surfaces while handling the instructions of a transaction, keyed by
+/// instruction index. Shared across
+/// every instruction (and cross-program invocation) in the transaction so
+/// the total log size can be capped.
+struct LogCollector {
+    messages: RefCell<Vec<(usize, String)>>,
+    bytes_used: Cell<usize>,
+    truncated: Cell<bool>,
+    return_data: RefCell<HashMap<usize, Vec<u8>>>,
+}
+
+impl LogCollector {
+    fn new() -> Self {
+        Self {
+            messages: RefCell::new(Vec::new()),
+            bytes_used: Cell::new(0),
+            truncated: Cell::new(false),
+            return_data: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Record a UTF-8 log message for `instruction_index`. Once the
+    /// transaction's total log size has been exceeded, a single truncation
+    /// marker is recorded instead and further messages are dropped, mirroring
+    /// how `verify_error` truncates an over-long `CustomError`. `truncated`
+    /// tracks whether that marker has already been emitted, independently of
+    /// `bytes_used`, so a message arriving exactly when the budget is already
+    /// full still gets the marker instead of being silently dropped.
+    fn log(&self, instruction_index: usize, message: &str) {
+        if self.truncated.get() {
+            return;
+        }
+        let bytes_used = self.bytes_used.get();
+        let mut messages = self.messages.borrow_mut();
+        if bytes_used + message.len() > MAX_LOG_BYTES {
+            messages.push((instruction_index, "Log truncated".to_string()));
+            self.truncated.set(true);
+            return;
+        }
+        self.bytes_used.set(bytes_used + message.len());
+        messages.push((instruction_index, message.to_string()));
+    }
+
+    /// Set `instruction_index`'s return-data blob, truncated to
+    /// `MAX_RETURN_DATA_BYTES`.
+    fn set_return_data(&self, instruction_index: usize, mut data: Vec<u8>) {
+        data.truncate(MAX_RETURN_DATA_BYTES);
+        self.return_data.borrow_mut().insert(instruction_index, data);
+    }
+}
+
+/// The log messages and return-data blob produced by each instruction of an
+/// executed transaction, indexed the same way as `tx.instructions`.
+#[derive(Debug, Default, PartialEq)]
+pub struct TransactionLogs {
+    pub log_messages: Vec<Vec<String>>,
+    pub return_data: Vec<Option<Vec<u8>>>,
+}
+
+impl TransactionLogs {
+    fn new(num_instructions: usize, collector: LogCollector) -> Self {
+        let mut log_messages = vec![Vec::new(); num_instructions];
+        for (instruction_index, message) in collector.messages.into_inner() {
+            log_messages[instruction_index].push(message);
+        }
+
+        let mut return_data = vec![None; num_instructions];
+        for (instruction_index, data) in collector.return_data.into_inner() {
+            return_data[instruction_index] = Some(data);
+        }
+
+        Self {
+            log_messages,
+            return_data,
+        }
+    }
+}
+
+/// Charge the fixed per-instruction cost plus `data_len + touched_bytes` bytes
+/// of work against `remaining_units`, failing without mutating it if the
+/// budget can't cover the cost.
+fn charge_compute_units(
+    compute_budget: &ComputeBudget,
+    remaining_units: &Cell<u64>,
+    data_len: usize,
+    touched_bytes: usize,
+) -> Result<(), InstructionError> {
+    let cost = compute_budget.instruction_cost
+        + (data_len as u64 + touched_bytes as u64) * compute_budget.byte_cost;
+    let remaining = remaining_units.get();
+    if cost > remaining {
+        return Err(InstructionError::ComputationalBudgetExceeded);
+    }
+    remaining_units.set(remaining - cost);
+    Ok(())
+}
 
 /// Return true if the slice has any duplicate elements
 pub fn has_duplicates<T: PartialEq>(xs: &[T]) -> bool {
@@ -18,11 +154,92 @@ pub fn has_duplicates<T: PartialEq>(xs: &[T]) -> bool {
     false
 }
 
-/// Get mut references to a subset of elements.
+/// Return true if the account at `index` in `tx.account_keys` was declared
+/// writable by the transaction. Accounts are writable unless they fall in
+/// the trailing readonly range of the signed or unsigned account lists.
+///
+/// A readonly count that's larger than the account list it applies to is
+/// malformed (it would mark every account in the list readonly and then
+/// some); we treat that the same as the largest valid count rather than
+/// underflowing, since this is exactly the kind of untrusted transaction
+/// input this access-control layer exists to handle safely.
+fn is_writable(tx: &Transaction, index: usize) -> bool {
+    let num_signed = tx.signatures.len();
+    if index < num_signed {
+        let num_writable_signed =
+            num_signed.saturating_sub(tx.num_readonly_signed_accounts as usize);
+        index < num_writable_signed
+    } else {
+        let num_writable_unsigned = tx
+            .account_keys
+            .len()
+            .saturating_sub(tx.num_readonly_unsigned_accounts as usize);
+        index < num_writable_unsigned
+    }
+}
+
+/// Partition transaction indexes into batches such that, within a batch, no
+/// two transactions' writable account sets overlap and so may be executed
+/// concurrently. Batches are returned in a fixed order, but that order is
+/// only a scheduling convenience, not a data dependency: `execute_transactions`
+/// runs each transaction over its own independently-loaded account snapshot,
+/// so a transaction in a later batch does not observe an earlier batch's
+/// writes to the same account, whether or not this function puts them in
+/// different batches.
+fn schedule_batches(writable_accounts: &[HashSet<Pubkey>]) -> Vec<Vec<usize>> {
+    let mut batches = Vec::new();
+    let mut remaining: Vec<usize> = (0..writable_accounts.len()).collect();
+
+    while !remaining.is_empty() {
+        let mut batch = Vec::new();
+        let mut batch_writes = HashSet::new();
+        let mut leftover = Vec::new();
+        for index in remaining {
+            if writable_accounts[index].is_disjoint(&batch_writes) {
+                batch_writes.extend(writable_accounts[index].iter().cloned());
+                batch.push(index);
+            } else {
+                leftover.push(index);
+            }
+        }
+        remaining = leftover;
+        batches.push(batch);
+    }
+
+    batches
+}
+
+/// Wraps a raw pointer so it can be captured by a `rayon` closure, which
+/// requires its environment to be `Sync`. Only used in `execute_transactions`,
+/// where every pointer dereferenced through this wrapper is offset to an
+/// index drawn from a single `schedule_batches` batch; those indexes are
+/// disjoint by construction, so distinct threads never touch the same slot.
+struct SendSyncPtr<T>(*mut T);
+unsafe impl<T> Send for SendSyncPtr<T> {}
+unsafe impl<T> Sync for SendSyncPtr<T> {}
+
+/// A reference to an account handed to a program, restricted to the access
+/// the transaction declared for it.
+pub enum AccountRef<'a, T> {
+    Writable(&'a mut T),
+    Readonly(&'a T),
+}
+
+impl<'a, T> AccountRef<'a, T> {
+    fn as_ref(&self) -> &T {
+        match self {
+            AccountRef::Writable(a) => a,
+            AccountRef::Readonly(a) => a,
+        }
+    }
+}
+
+/// Get references to a subset of elements, writable only where `is_writable` says so.
 fn get_subset_unchecked_mut<'a, T>(
     xs: &'a mut [T],
     indexes: &[u8],
-) -> Result<Vec<&'a mut T>, InstructionError> {
+    is_writable: &[bool],
+) -> Result<Vec<AccountRef<'a, T>>, InstructionError> {
     // Since the compiler doesn't know the indexes are unique, dereferencing
     // multiple mut elements is assumed to be unsafe. If, however, all
     // indexes are unique, it's perfectly safe. The returned elements will share
@@ -36,9 +253,14 @@ fn get_subset_unchecked_mut<'a, T>(
 
     Ok(indexes
         .iter()
-        .map(|i| {
+        .zip(is_writable.iter())
+        .map(|(i, writable)| {
             let ptr = &mut xs[*i as usize] as *mut T;
-            unsafe { &mut *ptr }
+            if *writable {
+                AccountRef::Writable(unsafe { &mut *ptr })
+            } else {
+                AccountRef::Readonly(unsafe { &*ptr })
+            }
         })
         .collect())
 }
@@ -48,6 +270,7 @@ fn verify_instruction(
     pre_program_id: &Pubkey,
     pre_lamports: u64,
     pre_data: &[u8],
+    is_writable: bool,
     account: &Account,
 ) -> Result<(), InstructionError> {
     // Verify the transaction
@@ -67,6 +290,14 @@ fn verify_instruction(
     {
         return Err(InstructionError::ExternalAccountDataModified);
     }
+    // An instruction may never touch the lamports or data of an account it
+    // didn't declare as writable, regardless of who owns it.
+    if !is_writable && pre_lamports != account.lamports {
+        return Err(InstructionError::ReadonlyLamportChange);
+    }
+    if !is_writable && pre_data != &account.data[..] {
+        return Err(InstructionError::ReadonlyDataModified);
+    }
     Ok(())
 }
 
@@ -81,10 +312,76 @@ fn verify_error(err: InstructionError) -> InstructionError {
 }
 
 pub type ProcessInstruction =
-    fn(&Pubkey, &mut [KeyedAccount], &[u8], u64) -> Result<(), InstructionError>;
+    fn(&Pubkey, &mut [KeyedAccount], &[u8], u64, &Invoker) -> Result<(), InstructionError>;
+
+/// Handle passed to a program's entrypoint that lets it invoke another
+/// program over a subset of the accounts it was already given, instead of
+/// returning control to the runtime after a single instruction.
+pub struct Invoker<'a> {
+    runtime: &'a Runtime,
+    tx: &'a Transaction,
+    tick_height: u64,
+    depth: usize,
+    instruction_index: usize,
+    remaining_units: &'a Cell<u64>,
+    log_collector: &'a LogCollector,
+}
+
+impl<'a> Invoker<'a> {
+    /// Invoke `instruction` as a child of the instruction currently being
+    /// executed, using `keyed_accounts` (a subset of the accounts the caller
+    /// already holds). The child inherits the caller's signer privileges and
+    /// cannot reach accounts the caller wasn't given. The compute budget and
+    /// log are shared with the rest of the transaction.
+    pub fn invoke(
+        &self,
+        instruction: &Instruction,
+        keyed_accounts: &mut [KeyedAccount],
+    ) -> Result<(), InstructionError> {
+        self.runtime.process_cross_program_instruction(
+            self.tx,
+            instruction,
+            keyed_accounts,
+            self.tick_height,
+            self.depth + 1,
+            self.instruction_index,
+            self.remaining_units,
+            self.log_collector,
+        )
+    }
+
+    /// Compute units left in the transaction's budget.
+    pub fn remaining_compute_units(&self) -> u64 {
+        self.remaining_units.get()
+    }
+
+    /// Explicitly charge `units` against the transaction's compute budget,
+    /// for programs that do work the runtime can't see (e.g. syscalls).
+    pub fn consume_compute_units(&self, units: u64) -> Result<(), InstructionError> {
+        let remaining = self.remaining_units.get();
+        if units > remaining {
+            return Err(InstructionError::ComputationalBudgetExceeded);
+        }
+        self.remaining_units.set(remaining - units);
+        Ok(())
+    }
+
+    /// Record a UTF-8 diagnostic message against the currently executing
+    /// instruction.
+    pub fn log(&self, message: &str) {
+        self.log_collector.log(self.instruction_index, message);
+    }
+
+    /// Set the return-data blob for the currently executing instruction.
+    pub fn set_return_data(&self, data: Vec<u8>) {
+        self.log_collector.set_return_data(self.instruction_index, data);
+    }
+}
 
 pub struct Runtime {
     instruction_processors: Vec<(Pubkey, ProcessInstruction)>,
+    max_invoke_depth: usize,
+    compute_budget: ComputeBudget,
 }
 
 impl Default for Runtime {
@@ -94,6 +391,8 @@ impl Default for Runtime {
 
         Self {
             instruction_processors,
+            max_invoke_depth: MAX_INVOKE_DEPTH,
+            compute_budget: ComputeBudget::default(),
         }
     }
 }
@@ -109,6 +408,142 @@ impl Runtime {
             .push((program_id, process_instruction));
     }
 
+    /// Override the maximum cross-program invocation depth. Mostly useful for tests.
+    pub fn set_max_invoke_depth(&mut self, max_invoke_depth: usize) {
+        self.max_invoke_depth = max_invoke_depth;
+    }
+
+    /// Override the per-transaction compute budget. Mostly useful for tests.
+    pub fn set_compute_budget(&mut self, compute_budget: ComputeBudget) {
+        self.compute_budget = compute_budget;
+    }
+
+    /// Process a cross-program invocation: a program, while handling `tx`'s
+    /// instruction at `depth - 1`, calls into another program over a subset
+    /// of the accounts it already holds.
+    ///
+    /// The accounts visible to the child are restricted to
+    /// `caller_keyed_accounts`, and each account's signer and writable flags
+    /// are taken from the caller rather than the child instruction's own
+    /// claim, so a called program can never gain privileges the caller
+    /// didn't already have. In particular, an account the caller holds
+    /// read-only stays read-only for the child, and is verified as such
+    /// afterward, the same as for a top-level instruction.
+    fn process_cross_program_instruction(
+        &self,
+        tx: &Transaction,
+        instruction: &Instruction,
+        caller_keyed_accounts: &mut [KeyedAccount],
+        tick_height: u64,
+        depth: usize,
+        instruction_index: usize,
+        remaining_units: &Cell<u64>,
+        log_collector: &LogCollector,
+    ) -> Result<(), InstructionError> {
+        if depth > self.max_invoke_depth {
+            return Err(InstructionError::CallDepthExceeded);
+        }
+
+        // Resolve each requested account to its position in
+        // `caller_keyed_accounts` up front, using only shared borrows, so the
+        // loop below never needs to re-query the slice while a previous
+        // iteration's mutable borrow of it is still alive.
+        let mut indexes = Vec::with_capacity(instruction.accounts.len());
+        for meta in &instruction.accounts {
+            let index = caller_keyed_accounts
+                .iter()
+                .position(|keyed_account| *keyed_account.unsigned_key() == meta.pubkey)
+                .ok_or(InstructionError::MissingAccount)?;
+            indexes.push(index as u8);
+        }
+        if has_duplicates(&indexes) {
+            return Err(InstructionError::DuplicateAccountIndex);
+        }
+
+        // Safe because `indexes` was just checked to contain no duplicates,
+        // so each raw pointer below targets a distinct element of
+        // `caller_keyed_accounts`, the same reasoning `get_subset_unchecked_mut`
+        // relies on for the top-level case.
+        let mut keyed_accounts = Vec::with_capacity(indexes.len());
+        let mut is_writable = Vec::with_capacity(indexes.len());
+        for &index in &indexes {
+            let caller_account =
+                unsafe { &mut *(&mut caller_keyed_accounts[index as usize] as *mut KeyedAccount) };
+            let is_signer = caller_account.signer_key().is_some();
+            // A child can never be more privileged than the caller: an account
+            // the caller only holds read-only is passed through as read-only,
+            // regardless of what the invoked instruction's own metadata claims.
+            let writable = caller_account.is_writable();
+            is_writable.push(writable);
+            keyed_accounts.push(if writable {
+                KeyedAccount::new(caller_account.unsigned_key(), is_signer, caller_account.account)
+            } else {
+                KeyedAccount::new_readonly(
+                    caller_account.unsigned_key(),
+                    is_signer,
+                    caller_account.account,
+                )
+            });
+        }
+
+        let (_, process_instruction) = self
+            .instruction_processors
+            .iter()
+            .find(|(id, _)| *id == instruction.program_id)
+            .ok_or(InstructionError::UnsupportedProgramId)?;
+
+        let pre_total: u64 = keyed_accounts.iter().map(|ka| ka.account.lamports).sum();
+        let pre_data: Vec<_> = keyed_accounts
+            .iter()
+            .map(|ka| (ka.account.owner, ka.account.lamports, ka.account.data.clone()))
+            .collect();
+
+        let touched_bytes: usize = keyed_accounts.iter().map(|ka| ka.account.data.len()).sum();
+        charge_compute_units(
+            &self.compute_budget,
+            remaining_units,
+            instruction.data.len(),
+            touched_bytes,
+        )?;
+
+        let invoker = Invoker {
+            runtime: self,
+            tx,
+            tick_height,
+            depth,
+            instruction_index,
+            remaining_units,
+            log_collector,
+        };
+        process_instruction(
+            &instruction.program_id,
+            &mut keyed_accounts,
+            &instruction.data,
+            tick_height,
+            &invoker,
+        )
+        .map_err(verify_error)?;
+
+        for ((pre_program_id, pre_lamports, pre_data), (keyed_account, is_writable)) in pre_data
+            .iter()
+            .zip(keyed_accounts.iter().zip(is_writable.iter()))
+        {
+            verify_instruction(
+                &instruction.program_id,
+                pre_program_id,
+                *pre_lamports,
+                pre_data,
+                *is_writable,
+                keyed_account.account,
+            )?;
+        }
+        let post_total: u64 = keyed_accounts.iter().map(|ka| ka.account.lamports).sum();
+        if pre_total != post_total {
+            return Err(InstructionError::UnbalancedInstruction);
+        }
+        Ok(())
+    }
+
     /// Process an instruction
     /// This method calls the instruction's program entrypoint method
     fn process_instruction(
@@ -116,10 +551,24 @@ impl Runtime {
         tx: &Transaction,
         instruction_index: usize,
         executable_accounts: &mut [(Pubkey, Account)],
-        program_accounts: &mut [&mut Account],
+        program_accounts: &mut [AccountRef<Account>],
         tick_height: u64,
+        remaining_units: &Cell<u64>,
+        log_collector: &LogCollector,
     ) -> Result<(), InstructionError> {
         let program_id = tx.program_id(instruction_index);
+        let instruction_data = &tx.instructions[instruction_index].data;
+        let touched_bytes: usize = executable_accounts
+            .iter()
+            .map(|(_, account)| account.data.len())
+            .chain(program_accounts.iter().map(|a| a.as_ref().data.len()))
+            .sum();
+        charge_compute_units(
+            &self.compute_budget,
+            remaining_units,
+            instruction_data.len(),
+            touched_bytes,
+        )?;
 
         let mut keyed_accounts = create_keyed_accounts(executable_accounts);
         let mut keyed_accounts2: Vec<_> = tx.instructions[instruction_index]
@@ -131,10 +580,23 @@ impl Runtime {
                 (key, index < tx.signatures.len())
             })
             .zip(program_accounts.iter_mut())
-            .map(|((key, is_signer), account)| KeyedAccount::new(key, is_signer, account))
+            .map(|((key, is_signer), account)| match account {
+                AccountRef::Writable(account) => KeyedAccount::new(key, is_signer, account),
+                AccountRef::Readonly(account) => KeyedAccount::new_readonly(key, is_signer, account),
+            })
             .collect();
         keyed_accounts.append(&mut keyed_accounts2);
 
+        let invoker = Invoker {
+            runtime: self,
+            tx,
+            tick_height,
+            depth: 0,
+            instruction_index,
+            remaining_units,
+            log_collector,
+        };
+
         for (id, process_instruction) in &self.instruction_processors {
             if id == program_id {
                 return process_instruction(
@@ -142,6 +604,7 @@ impl Runtime {
                     &mut keyed_accounts[1..],
                     &tx.instructions[instruction_index].data,
                     tick_height,
+                    &invoker,
                 );
             }
         }
@@ -151,6 +614,7 @@ impl Runtime {
             &mut keyed_accounts,
             &tx.instructions[instruction_index].data,
             tick_height,
+            &invoker,
         )
     }
 
@@ -163,16 +627,23 @@ impl Runtime {
         tx: &Transaction,
         instruction_index: usize,
         executable_accounts: &mut [(Pubkey, Account)],
-        program_accounts: &mut [&mut Account],
+        program_accounts: &mut [AccountRef<Account>],
         tick_height: u64,
+        remaining_units: &Cell<u64>,
+        log_collector: &LogCollector,
     ) -> Result<(), InstructionError> {
         let program_id = tx.program_id(instruction_index);
-        // TODO: the runtime should be checking read/write access to memory
-        // we are trusting the hard-coded programs not to clobber or allocate
-        let pre_total: u64 = program_accounts.iter().map(|a| a.lamports).sum();
+        let is_writable: Vec<bool> = program_accounts
+            .iter()
+            .map(|a| matches!(a, AccountRef::Writable(_)))
+            .collect();
+        let pre_total: u64 = program_accounts.iter().map(|a| a.as_ref().lamports).sum();
         let pre_data: Vec<_> = program_accounts
-            .iter_mut()
-            .map(|a| (a.owner, a.lamports, a.data.clone()))
+            .iter()
+            .map(|a| {
+                let a = a.as_ref();
+                (a.owner, a.lamports, a.data.clone())
+            })
             .collect();
 
         self.process_instruction(
@@ -181,23 +652,28 @@ impl Runtime {
             executable_accounts,
             program_accounts,
             tick_height,
+            remaining_units,
+            log_collector,
         )
         .map_err(verify_error)?;
 
         // Verify the instruction
-        for ((pre_program_id, pre_lamports, pre_data), post_account) in
-            pre_data.iter().zip(program_accounts.iter())
+        for (((pre_program_id, pre_lamports, pre_data), post_account), is_writable) in pre_data
+            .iter()
+            .zip(program_accounts.iter())
+            .zip(is_writable.iter())
         {
             verify_instruction(
                 &program_id,
                 pre_program_id,
                 *pre_lamports,
                 pre_data,
-                post_account,
+                *is_writable,
+                post_account.as_ref(),
             )?;
         }
         // The total sum of all the lamports in all the accounts cannot change.
-        let post_total: u64 = program_accounts.iter().map(|a| a.lamports).sum();
+        let post_total: u64 = program_accounts.iter().map(|a| a.as_ref().lamports).sum();
         if pre_total != post_total {
             return Err(InstructionError::UnbalancedInstruction);
         }
@@ -213,28 +689,125 @@ impl Runtime {
         loaders: &mut [Vec<(Pubkey, Account)>],
         tx_accounts: &mut [Account],
         tick_height: u64,
+    ) -> (Result<(), TransactionError>, TransactionLogs) {
+        let remaining_units = Cell::new(self.compute_budget.max_units);
+        let log_collector = LogCollector::new();
+        let result = self.execute_transaction_instructions(
+            tx,
+            loaders,
+            tx_accounts,
+            tick_height,
+            &remaining_units,
+            &log_collector,
+        );
+        (result, TransactionLogs::new(tx.instructions.len(), log_collector))
+    }
+
+    fn execute_transaction_instructions(
+        &self,
+        tx: &Transaction,
+        loaders: &mut [Vec<(Pubkey, Account)>],
+        tx_accounts: &mut [Account],
+        tick_height: u64,
+        remaining_units: &Cell<u64>,
+        log_collector: &LogCollector,
     ) -> Result<(), TransactionError> {
         for (instruction_index, instruction) in tx.instructions.iter().enumerate() {
             let executable_accounts = &mut loaders[instruction.program_ids_index as usize];
-            let mut program_accounts = get_subset_unchecked_mut(tx_accounts, &instruction.accounts)
-                .map_err(|err| TransactionError::InstructionError(instruction_index as u8, err))?;
+            let is_writable: Vec<bool> = instruction
+                .accounts
+                .iter()
+                .map(|&index| is_writable(tx, index as usize))
+                .collect();
+            let mut program_accounts =
+                get_subset_unchecked_mut(tx_accounts, &instruction.accounts, &is_writable)
+                    .map_err(|err| TransactionError::InstructionError(instruction_index as u8, err))?;
             self.execute_instruction(
                 tx,
                 instruction_index,
                 executable_accounts,
                 &mut program_accounts,
                 tick_height,
+                remaining_units,
+                log_collector,
             )
             .map_err(|err| TransactionError::InstructionError(instruction_index as u8, err))?;
         }
         Ok(())
     }
+
+    /// Execute a set of mutually independent transactions, running those
+    /// whose writable accounts don't overlap with any other transaction's
+    /// concurrently on a thread pool. Results are returned in the same order
+    /// as `txs`. Each transaction's accounts are committed atomically only on
+    /// that transaction's own success, exactly as with `execute_transaction`.
+    ///
+    /// This is a parallelism optimization only, not a dependency scheduler:
+    /// `loaders` and `tx_accounts` hold one independent, pre-loaded snapshot
+    /// per transaction, and this function never reloads or merges one
+    /// transaction's results into another's slot. Two transactions that
+    /// write the same account are still run one after the other rather than
+    /// concurrently (to keep the unsafe indexing below sound), but that
+    /// ordering gives the later one no visibility into the earlier one's
+    /// writes — it sees the same stale snapshot it would have if both ran
+    /// fully in parallel. Do not pass transactions here expecting one to
+    /// observe another's effects; callers with that requirement must load
+    /// and run them sequentially themselves, e.g. via `execute_transaction`.
+    pub fn execute_transactions(
+        &self,
+        txs: &[Transaction],
+        loaders: &mut [Vec<Vec<(Pubkey, Account)>>],
+        tx_accounts: &mut [Vec<Account>],
+        tick_height: u64,
+    ) -> Vec<(Result<(), TransactionError>, TransactionLogs)> {
+        let writable_accounts: Vec<HashSet<Pubkey>> = txs
+            .iter()
+            .map(|tx| {
+                tx.instructions
+                    .iter()
+                    .flat_map(|instruction| instruction.accounts.iter())
+                    .filter(|&&index| is_writable(tx, index as usize))
+                    .map(|&index| tx.account_keys[index as usize])
+                    .collect()
+            })
+            .collect();
+
+        let mut results: Vec<Option<(Result<(), TransactionError>, TransactionLogs)>> =
+            (0..txs.len()).map(|_| None).collect();
+
+        for batch in schedule_batches(&writable_accounts) {
+            // Safe because `batch` holds distinct indexes into `loaders` and
+            // `tx_accounts`, so each transaction in it gets exclusive access
+            // to its own slot.
+            let loaders_ptr = SendSyncPtr(loaders.as_mut_ptr());
+            let tx_accounts_ptr = SendSyncPtr(tx_accounts.as_mut_ptr());
+            let batch_results: Vec<(usize, (Result<(), TransactionError>, TransactionLogs))> = batch
+                .par_iter()
+                .map(|&index| {
+                    let tx_loaders = unsafe { &mut *loaders_ptr.0.add(index) };
+                    let accounts = unsafe { &mut *tx_accounts_ptr.0.add(index) };
+                    let result =
+                        self.execute_transaction(&txs[index], tx_loaders, accounts, tick_height);
+                    (index, result)
+                })
+                .collect();
+
+            for (index, result) in batch_results {
+                results[index] = Some(result);
+            }
+        }
+
+        results.into_iter().map(|result| result.unwrap()).collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use solana_sdk::hash::Hash;
+    use solana_sdk::message::Message;
     use solana_sdk::signature::{Keypair, KeypairUtil};
+    use solana_sdk::system_instruction::SystemInstruction;
 
     #[test]
     fn test_has_duplicates() {
@@ -242,23 +815,193 @@ mod tests {
         assert!(has_duplicates(&[1, 2, 1]));
     }
 
+    #[test]
+    fn test_is_writable() {
+        let keypair = Keypair::new();
+        let to = Keypair::new().pubkey();
+        let instruction = SystemInstruction::new_account(&keypair.pubkey(), &to, 1);
+        let message = Message::new(vec![instruction]);
+        let blockhash = Hash::new(&to.as_ref());
+        let mut tx = Transaction::new(&[&keypair], message, blockhash);
+
+        assert!(is_writable(&tx, 0), "a signer is writable by default");
+
+        tx.num_readonly_signed_accounts = 1;
+        assert!(!is_writable(&tx, 0), "a declared-readonly signer is not writable");
+
+        tx.num_readonly_signed_accounts = 100;
+        assert!(
+            !is_writable(&tx, 0),
+            "a readonly count larger than the signer list must not underflow"
+        );
+
+        tx.num_readonly_signed_accounts = 0;
+        tx.num_readonly_unsigned_accounts = 100;
+        let last_index = tx.account_keys.len() - 1;
+        assert!(
+            !is_writable(&tx, last_index),
+            "a readonly count larger than the account list must not underflow"
+        );
+    }
+
+    #[test]
+    fn test_schedule_batches_disjoint_writes_run_together() {
+        let a = Keypair::new().pubkey();
+        let b = Keypair::new().pubkey();
+        let c = Keypair::new().pubkey();
+
+        let writable_accounts = vec![
+            [a].iter().cloned().collect(),
+            [b].iter().cloned().collect(),
+            [c].iter().cloned().collect(),
+        ];
+
+        let batches = schedule_batches(&writable_accounts);
+        assert_eq!(batches.len(), 1);
+        let mut batch = batches[0].clone();
+        batch.sort();
+        assert_eq!(batch, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_schedule_batches_overlapping_writes_are_serialized() {
+        let a = Keypair::new().pubkey();
+        let b = Keypair::new().pubkey();
+
+        let writable_accounts = vec![
+            [a].iter().cloned().collect(),
+            [a, b].iter().cloned().collect(),
+            [b].iter().cloned().collect(),
+        ];
+
+        let batches = schedule_batches(&writable_accounts);
+        assert_eq!(batches, vec![vec![0, 2], vec![1]]);
+    }
+
+    #[test]
+    fn test_execute_transactions_does_not_see_writes_across_transactions() {
+        fn credit_lamports(
+            _program_id: &Pubkey,
+            keyed_accounts: &mut [KeyedAccount],
+            _data: &[u8],
+            _tick_height: u64,
+            _invoker: &Invoker,
+        ) -> Result<(), InstructionError> {
+            keyed_accounts[0].account.lamports += 1;
+            Ok(())
+        }
+
+        let mut runtime = Runtime::default();
+        let program_id = Keypair::new().pubkey();
+        runtime.add_instruction_processor(program_id, credit_lamports);
+
+        let shared = Keypair::new().pubkey();
+        let blockhash = Hash::new(&shared.as_ref());
+        let build_tx = || {
+            let keypair = Keypair::new();
+            let instruction = Instruction {
+                program_id,
+                accounts: vec![AccountMeta::new(shared, false)],
+                data: vec![],
+            };
+            let message = Message::new(vec![instruction]);
+            Transaction::new(&[&keypair], message, blockhash)
+        };
+        let txs = vec![build_tx(), build_tx()];
+
+        // Both transactions write `shared`, so they're forced into separate
+        // batches...
+        let writable_accounts: Vec<HashSet<Pubkey>> = vec![
+            [shared].iter().cloned().collect(),
+            [shared].iter().cloned().collect(),
+        ];
+        assert_eq!(
+            schedule_batches(&writable_accounts),
+            vec![vec![0], vec![1]],
+            "two transactions writing the same account must not share a batch"
+        );
+
+        // ...but `execute_transactions` still gives each transaction its own
+        // pre-loaded snapshot of `shared`, so the second transaction's batch
+        // running "after" the first buys it nothing: it starts from the same
+        // lamport count, not whatever the first transaction committed.
+        let program_account = Account::new(0, 0, &program_id);
+        let starting_account = Account::new(1, 0, &program_id);
+        let mut loaders: Vec<Vec<Vec<(Pubkey, Account)>>> = txs
+            .iter()
+            .map(|tx| {
+                let mut per_program_slot = vec![Vec::new(); tx.account_keys.len()];
+                let program_index = tx.instructions[0].program_ids_index as usize;
+                per_program_slot[program_index] = vec![(program_id, program_account.clone())];
+                per_program_slot
+            })
+            .collect();
+        let mut tx_accounts: Vec<Vec<Account>> = txs
+            .iter()
+            .map(|tx| {
+                tx.account_keys
+                    .iter()
+                    .map(|key| {
+                        if *key == shared {
+                            starting_account.clone()
+                        } else if *key == program_id {
+                            program_account.clone()
+                        } else {
+                            Account::new(0, 0, &program_id)
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let results = runtime.execute_transactions(&txs, &mut loaders, &mut tx_accounts, 0);
+        assert!(results[0].0.is_ok());
+        assert!(results[1].0.is_ok());
+
+        for (tx, accounts) in txs.iter().zip(tx_accounts.iter()) {
+            let shared_index = tx.account_keys.iter().position(|key| *key == shared).unwrap();
+            assert_eq!(
+                accounts[shared_index].lamports, 2,
+                "each transaction only ever sees its own starting snapshot of `shared`, \
+                 never the other transaction's write to it"
+            );
+        }
+    }
+
+    fn as_values(refs: &[AccountRef<i32>]) -> Vec<i32> {
+        refs.iter().map(|a| *a.as_ref()).collect()
+    }
+
     #[test]
     fn test_get_subset_unchecked_mut() {
         assert_eq!(
-            get_subset_unchecked_mut(&mut [7, 8], &[0]).unwrap(),
-            vec![&mut 7]
+            as_values(&get_subset_unchecked_mut(&mut [7, 8], &[0], &[true]).unwrap()),
+            vec![7]
         );
         assert_eq!(
-            get_subset_unchecked_mut(&mut [7, 8], &[0, 1]).unwrap(),
-            vec![&mut 7, &mut 8]
+            as_values(&get_subset_unchecked_mut(&mut [7, 8], &[0, 1], &[true, true]).unwrap()),
+            vec![7, 8]
         );
     }
 
+    #[test]
+    fn test_get_subset_unchecked_mut_readonly() {
+        let refs = get_subset_unchecked_mut(&mut [7, 8], &[0, 1], &[true, false]).unwrap();
+        assert!(match refs[0] {
+            AccountRef::Writable(_) => true,
+            AccountRef::Readonly(_) => false,
+        });
+        assert!(match refs[1] {
+            AccountRef::Writable(_) => false,
+            AccountRef::Readonly(_) => true,
+        });
+    }
+
     #[test]
     fn test_get_subset_unchecked_mut_duplicate_index() {
         // This panics, because it assumes duplicate detection is done elsewhere.
         assert_eq!(
-            get_subset_unchecked_mut(&mut [7, 8], &[0, 0]).unwrap_err(),
+            get_subset_unchecked_mut(&mut [7, 8], &[0, 0], &[true, true]).unwrap_err(),
             InstructionError::DuplicateAccountIndex
         );
     }
@@ -267,7 +1010,7 @@ mod tests {
     #[should_panic]
     fn test_get_subset_unchecked_mut_out_of_bounds() {
         // This panics, because it assumes bounds validation is done elsewhere.
-        get_subset_unchecked_mut(&mut [7, 8], &[2]).unwrap();
+        get_subset_unchecked_mut(&mut [7, 8], &[2], &[true]).unwrap();
     }
 
     #[test]
@@ -277,7 +1020,7 @@ mod tests {
             pre: &Pubkey,
             post: &Pubkey,
         ) -> Result<(), InstructionError> {
-            verify_instruction(&ix, &pre, 0, &[], &Account::new(0, 0, post))
+            verify_instruction(&ix, &pre, 0, &[], true, &Account::new(0, 0, post))
         }
 
         let system_program_id = system_program::id();
@@ -301,7 +1044,7 @@ mod tests {
         fn change_data(program_id: &Pubkey) -> Result<(), InstructionError> {
             let alice_program_id = Keypair::new().pubkey();
             let account = Account::new(0, 0, &alice_program_id);
-            verify_instruction(&program_id, &alice_program_id, 0, &[42], &account)
+            verify_instruction(&program_id, &alice_program_id, 0, &[42], true, &account)
         }
 
         let system_program_id = system_program::id();
@@ -319,6 +1062,185 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_verify_instruction_readonly_lamport_change() {
+        let program_id = system_program::id();
+        let account = Account::new(1, 0, &program_id);
+
+        assert_eq!(
+            verify_instruction(&program_id, &program_id, 1, &[], false, &account),
+            Ok(()),
+            "unchanged lamports in a readonly account should be fine"
+        );
+        assert_eq!(
+            verify_instruction(&program_id, &program_id, 0, &[], false, &account),
+            Err(InstructionError::ReadonlyLamportChange),
+            "a readonly account's lamports must not change"
+        );
+    }
+
+    #[test]
+    fn test_verify_instruction_readonly_data_modified() {
+        let program_id = system_program::id();
+        let mut account = Account::new(0, 0, &program_id);
+        account.data = vec![1, 2, 3];
+
+        assert_eq!(
+            verify_instruction(&program_id, &program_id, 0, &[1, 2, 3], false, &account),
+            Ok(()),
+            "unchanged data in a readonly account should be fine"
+        );
+        assert_eq!(
+            verify_instruction(&program_id, &program_id, 0, &[9, 9, 9], false, &account),
+            Err(InstructionError::ReadonlyDataModified),
+            "a readonly account's data must not change"
+        );
+    }
+
+    #[test]
+    fn test_process_cross_program_instruction_call_depth_exceeded() {
+        let runtime = Runtime::default();
+        let keypair = Keypair::new();
+        let to = Keypair::new().pubkey();
+        let instruction = SystemInstruction::new_account(&keypair.pubkey(), &to, 1);
+        let message = Message::new(vec![instruction.clone()]);
+        let blockhash = Hash::new(&to.as_ref());
+        let tx = Transaction::new(&[&keypair], message, blockhash);
+
+        let remaining_units = Cell::new(runtime.compute_budget.max_units);
+        let log_collector = LogCollector::new();
+        assert_eq!(
+            runtime.process_cross_program_instruction(
+                &tx,
+                &instruction,
+                &mut [],
+                0,
+                runtime.max_invoke_depth + 1,
+                0,
+                &remaining_units,
+                &log_collector,
+            ),
+            Err(InstructionError::CallDepthExceeded),
+            "a program should not be able to invoke past the configured max depth"
+        );
+    }
+
+    #[test]
+    fn test_process_cross_program_instruction_propagates_readonly() {
+        fn credit_lamports(
+            _program_id: &Pubkey,
+            keyed_accounts: &mut [KeyedAccount],
+            _data: &[u8],
+            _tick_height: u64,
+            _invoker: &Invoker,
+        ) -> Result<(), InstructionError> {
+            keyed_accounts[0].account.lamports += 1;
+            Ok(())
+        }
+
+        let mut runtime = Runtime::default();
+        let callee_program_id = Keypair::new().pubkey();
+        runtime.add_instruction_processor(callee_program_id, credit_lamports);
+
+        let target = Keypair::new().pubkey();
+        let mut target_account = Account::new(1, 0, &system_program::id());
+        let mut caller_keyed_accounts =
+            vec![KeyedAccount::new_readonly(&target, false, &mut target_account)];
+
+        let instruction = Instruction {
+            program_id: callee_program_id,
+            accounts: vec![AccountMeta::new(target, false)],
+            data: vec![],
+        };
+
+        let keypair = Keypair::new();
+        let to = Keypair::new().pubkey();
+        let sys_instruction = SystemInstruction::new_account(&keypair.pubkey(), &to, 1);
+        let message = Message::new(vec![sys_instruction]);
+        let blockhash = Hash::new(&to.as_ref());
+        let tx = Transaction::new(&[&keypair], message, blockhash);
+
+        let remaining_units = Cell::new(runtime.compute_budget.max_units);
+        let log_collector = LogCollector::new();
+        assert_eq!(
+            runtime.process_cross_program_instruction(
+                &tx,
+                &instruction,
+                &mut caller_keyed_accounts,
+                0,
+                1,
+                0,
+                &remaining_units,
+                &log_collector,
+            ),
+            Err(InstructionError::ReadonlyLamportChange),
+            "an account the caller only holds read-only must stay read-only through CPI, \
+             even when the callee tries to credit it"
+        );
+    }
+
+    #[test]
+    fn test_charge_compute_units_exceeded() {
+        let compute_budget = ComputeBudget {
+            max_units: 100,
+            instruction_cost: 100,
+            byte_cost: 1,
+        };
+        let remaining_units = Cell::new(compute_budget.max_units);
+
+        assert_eq!(
+            charge_compute_units(&compute_budget, &remaining_units, 0, 0),
+            Ok(()),
+            "an instruction that exactly exhausts the budget should still succeed"
+        );
+        assert_eq!(remaining_units.get(), 0);
+        assert_eq!(
+            charge_compute_units(&compute_budget, &remaining_units, 0, 0),
+            Err(InstructionError::ComputationalBudgetExceeded),
+            "an instruction run with no budget left should be rejected"
+        );
+    }
+
+    #[test]
+    fn test_log_collector_per_instruction_messages() {
+        let collector = LogCollector::new();
+        collector.log(0, "hello from instruction 0");
+        collector.log(1, "hello from instruction 1");
+        collector.log(0, "hello again from instruction 0");
+
+        let logs = TransactionLogs::new(2, collector);
+        assert_eq!(
+            logs.log_messages,
+            vec![
+                vec![
+                    "hello from instruction 0".to_string(),
+                    "hello again from instruction 0".to_string(),
+                ],
+                vec!["hello from instruction 1".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_log_collector_truncates_when_over_budget() {
+        let collector = LogCollector::new();
+        collector.log(0, &"x".repeat(MAX_LOG_BYTES));
+        collector.log(0, "this should be dropped in favor of a truncation marker");
+
+        let logs = TransactionLogs::new(1, collector);
+        assert_eq!(logs.log_messages[0].len(), 2);
+        assert_eq!(logs.log_messages[0][1], "Log truncated");
+    }
+
+    #[test]
+    fn test_log_collector_return_data_is_capped() {
+        let collector = LogCollector::new();
+        collector.set_return_data(0, vec![7; MAX_RETURN_DATA_BYTES + 10]);
+
+        let logs = TransactionLogs::new(1, collector);
+        assert_eq!(logs.return_data[0].as_ref().unwrap().len(), MAX_RETURN_DATA_BYTES);
+    }
+
     #[test]
     fn test_verify_error() {
         let short_error = InstructionError::CustomError(vec![1, 2, 3]);